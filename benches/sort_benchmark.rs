@@ -23,7 +23,15 @@ fn create_test_data(size: usize) -> String {
 
 fn run_sort(input: &str, output: &str, batch_size: usize) {
     Command::new("target/release/sort_bigger_then_ram")
-        .args(["-i", input, "-o", output, "-b", &batch_size.to_string()])
+        .args([
+            "-i",
+            input,
+            "-o",
+            output,
+            "-b",
+            &batch_size.to_string(),
+            "-n",
+        ])
         .output()
         .expect("Failed to run sort");
 }
@@ -1,13 +1,29 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Parser;
-use std::cmp::Reverse;
 use std::{
+    cmp::Ordering,
     collections::BinaryHeap,
-    io::{BufRead, BufReader, Write},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering as AtomicOrdering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Instant,
 };
 
 const TEMP_FILE_PREFIX: &str = "sort_temp_file_";
 
+/// Size of each raw read performed by the reader thread.
+const READ_BLOCK_BYTES: usize = 16 * 1024 * 1024;
+
+/// Number of parsed batches that may queue between the reader thread and the
+/// sort workers before the reader blocks. Emptied buffers return to the
+/// reader over a separate unbounded channel; the reader allocates a fresh one
+/// whenever none is waiting there.
+const BATCH_CHANNEL_DEPTH: usize = 3;
+
 /// Sort a file that is bigger than RAM
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -23,6 +39,168 @@ struct Sorter {
     /// Batch size in megabytes, default 1024 MB
     #[arg(short, long, default_value_t = 1024)]
     batch: usize,
+
+    /// Maximum number of runs merged together in a single pass, default 64.
+    /// Keeps the merge step from opening more files than the OS fd limit
+    /// allows; inputs producing more runs than this are merged in multiple
+    /// passes instead. Must be at least 2: a fanout of 0 would divide by
+    /// zero and a fanout of 1 would never shrink the run count, so both are
+    /// rejected at the CLI level instead of panicking or looping forever.
+    #[arg(short = 'f', long, default_value_t = 64, value_parser = parse_min_fanout)]
+    max_fanout: usize,
+
+    /// Number of worker threads sorting and spilling batches concurrently,
+    /// default the number of available CPUs
+    #[arg(short = 'j', long, default_value_t = default_thread_count())]
+    threads: usize,
+
+    /// Compare lines as u64 numbers instead of byte strings
+    #[arg(short = 'n', long)]
+    numeric: bool,
+
+    /// Emit lines in descending order
+    #[arg(short = 'r', long)]
+    reverse: bool,
+
+    /// Drop adjacent duplicate lines from the output
+    #[arg(short = 'u', long)]
+    unique: bool,
+
+    /// Print a summary of counts and phase timings to stderr once sorting
+    /// finishes
+    #[arg(short = 's', long)]
+    stats: bool,
+}
+
+/// Falls back to a single thread when the platform can't report a core count.
+fn default_thread_count() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// Parses `--max-fanout`, rejecting anything below 2. `clap::value_parser!`
+/// only hands back a range-checked parser (with a `.range()` method) for the
+/// fixed-width integer types, not `usize`, so the lower bound is enforced
+/// with this manual parser instead.
+fn parse_min_fanout(s: &str) -> std::result::Result<usize, String> {
+    let value: usize = s.parse().map_err(|_| format!("`{s}` is not a valid number"))?;
+    if value < 2 {
+        return Err("must be at least 2".to_string());
+    }
+    Ok(value)
+}
+
+/// A single input line, keyed the way `--numeric` asks for: a parsed `u64`
+/// so numbers compare by value, or the raw text so arbitrary lines compare
+/// lexically. A run only ever holds one variant, matching the CLI mode it
+/// was produced under.
+#[derive(Clone, Debug)]
+enum Record {
+    Numeric(u64),
+    Lexical(String),
+}
+
+impl Record {
+    fn cmp_key(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Record::Numeric(a), Record::Numeric(b)) => a.cmp(b),
+            (Record::Lexical(a), Record::Lexical(b)) => a.cmp(b),
+            _ => unreachable!("a single sort run never mixes numeric and lexical records"),
+        }
+    }
+}
+
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp_key(other) == Ordering::Equal
+    }
+}
+impl Eq for Record {}
+
+/// Min/max-heap entry for the tournament merge. `reverse` is baked into each
+/// entry (rather than relying on ambient state) so `Ord` alone decides
+/// whether `BinaryHeap::pop` yields ascending or descending order.
+struct HeapEntry {
+    record: Record,
+    run_idx: usize,
+    reverse: bool,
+}
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.record == other.record
+    }
+}
+impl Eq for HeapEntry {}
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let ord = self.record.cmp_key(&other.record);
+        if self.reverse {
+            ord
+        } else {
+            ord.reverse()
+        }
+    }
+}
+
+/// Counts and phase timings collected across the read/spill and merge
+/// stages, printed as a `--stats` report once sorting finishes. The counters
+/// are atomics because the reader and worker threads update them
+/// concurrently; the phase durations are only ever written once, by `main`,
+/// after each phase's threads have been joined.
+#[derive(Default)]
+struct Metrics {
+    values_read: AtomicU64,
+    bytes_read: AtomicU64,
+    runs_spilled: AtomicU64,
+    merge_passes: AtomicU64,
+    peak_open_files: AtomicU64,
+    read_spill_elapsed: Mutex<std::time::Duration>,
+    merge_elapsed: Mutex<std::time::Duration>,
+}
+
+impl Metrics {
+    fn record_open_files(&self, count: u64) {
+        self.peak_open_files.fetch_max(count, AtomicOrdering::Relaxed);
+    }
+
+    fn report(&self) {
+        eprintln!("sort statistics:");
+        eprintln!(
+            "  values read      : {}",
+            self.values_read.load(AtomicOrdering::Relaxed)
+        );
+        eprintln!(
+            "  bytes read       : {}",
+            self.bytes_read.load(AtomicOrdering::Relaxed)
+        );
+        eprintln!(
+            "  runs spilled     : {}",
+            self.runs_spilled.load(AtomicOrdering::Relaxed)
+        );
+        eprintln!(
+            "  merge passes     : {}",
+            self.merge_passes.load(AtomicOrdering::Relaxed)
+        );
+        eprintln!(
+            "  peak open files  : {}",
+            self.peak_open_files.load(AtomicOrdering::Relaxed)
+        );
+        eprintln!(
+            "  read+spill phase : {:?}",
+            *self.read_spill_elapsed.lock().unwrap()
+        );
+        eprintln!(
+            "  merge phase      : {:?}",
+            *self.merge_elapsed.lock().unwrap()
+        );
+    }
 }
 
 impl Sorter {
@@ -34,87 +212,357 @@ impl Sorter {
         }
     }
 
-    fn sort_and_write_to_file(&self, batch_sorted: &mut [u64], file_num: u64) -> Result<()> {
-        batch_sorted.sort_unstable();
+    fn key_cmp(&self, a: &Record, b: &Record) -> Ordering {
+        let ord = a.cmp_key(b);
+        if self.reverse {
+            ord.reverse()
+        } else {
+            ord
+        }
+    }
+
+    /// Writes a single record to a run file. Numeric runs always use the
+    /// compact 8-byte little-endian spill format; lexical runs and the final
+    /// `self.output` (`final_pass`) are always newline-terminated text.
+    fn write_record<W: Write>(&self, output: &mut W, record: &Record, final_pass: bool) -> Result<()> {
+        match record {
+            Record::Numeric(num) if !final_pass => {
+                output.write_all(&num.to_le_bytes())?;
+            }
+            Record::Numeric(num) => writeln!(output, "{}", num)?,
+            Record::Lexical(line) => writeln!(output, "{}", line)?,
+        }
+
+        Ok(())
+    }
+
+    /// Reads the next record back out of a run file, in whichever format
+    /// `write_record` used for it. A clean end-of-file reports `None`.
+    fn read_record<R: Read + BufRead>(&self, reader: &mut R) -> Result<Option<Record>> {
+        if self.numeric {
+            Ok(read_next_u64(reader)?.map(Record::Numeric))
+        } else {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Ok(Some(Record::Lexical(line)))
+        }
+    }
+
+    /// Sorts `batch_sorted` and spills it to a run temp file in its native
+    /// spill format (binary for numeric mode, text for lexical mode).
+    fn sort_and_write_to_file(
+        &self,
+        batch_sorted: &mut [Record],
+        file_num: u64,
+        metrics: &Metrics,
+    ) -> Result<()> {
+        batch_sorted.sort_unstable_by(|a, b| self.key_cmp(a, b));
         let temp_file_path = self.get_temp_file_path(file_num);
-        let mut file = std::fs::File::create(&temp_file_path)?;
-        for num in batch_sorted {
-            writeln!(file, "{}", num)?;
+        let mut file = BufWriter::new(std::fs::File::create(&temp_file_path)?);
+        for record in batch_sorted.iter() {
+            self.write_record(&mut file, record, false)?;
         }
+        file.flush()?;
+        metrics.runs_spilled.fetch_add(1, AtomicOrdering::Relaxed);
 
         Ok(())
     }
 
-    fn merge_files(&self, files_num: u64) -> Result<()> {
+    /// Merges the run temp files `0..files_num` into `self.output`. Runs are
+    /// merged in groups of at most `max_fanout` files at a time: if there are
+    /// more runs than that, groups are first folded pass-by-pass into fewer,
+    /// larger intermediate runs (each pass deleting the runs it consumed)
+    /// until at most `max_fanout` remain, which are then merged straight into
+    /// `self.output`. This keeps the number of simultaneously open files
+    /// bounded regardless of how many runs the spill phase produced.
+    fn merge_files(&self, files_num: u64, metrics: &Metrics) -> Result<()> {
         if files_num == 0 {
             std::fs::File::create(&self.output)?;
             return Ok(());
         }
 
-        let mut readers = (0..files_num)
-            .map(|file_num| {
+        let mut run_ids: Vec<u64> = (0..files_num).collect();
+        let mut next_file_id = files_num;
+
+        while run_ids.len() > self.max_fanout {
+            metrics.merge_passes.fetch_add(1, AtomicOrdering::Relaxed);
+            let group_count = run_ids.len().div_ceil(self.max_fanout);
+            let mut next_round = Vec::with_capacity(group_count);
+
+            for group in run_ids.chunks(self.max_fanout) {
+                let merged_id = next_file_id;
+                next_file_id += 1;
+                let temp_file_path = self.get_temp_file_path(merged_id);
+                let file = std::fs::File::create(&temp_file_path)?;
+                self.merge_run_group(group, file, false, metrics)?;
+                next_round.push(merged_id);
+            }
+
+            run_ids = next_round;
+        }
+
+        metrics.merge_passes.fetch_add(1, AtomicOrdering::Relaxed);
+        let output_file = std::fs::File::create(&self.output)?;
+        self.merge_run_group(&run_ids, output_file, true, metrics)?;
+
+        Ok(())
+    }
+
+    /// Tournament-merges the given run ids into `output` via a `BinaryHeap`,
+    /// then deletes the run temp files once fully consumed. Only on the
+    /// final pass into `self.output` (`final_pass`) does `--unique` drop a
+    /// value that equals the last one written, since that's the only point
+    /// where every run's values are seen in one globally sorted sequence.
+    fn merge_run_group<W: Write>(
+        &self,
+        run_ids: &[u64],
+        mut output: W,
+        final_pass: bool,
+        metrics: &Metrics,
+    ) -> Result<()> {
+        let mut readers = run_ids
+            .iter()
+            .map(|&file_num| {
                 let temp_file_path = self.get_temp_file_path(file_num);
-                BufReader::new(std::fs::File::open(&temp_file_path).unwrap()).lines()
+                Ok::<_, anyhow::Error>(BufReader::new(std::fs::File::open(&temp_file_path)?))
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>>>()?;
+        metrics.record_open_files(run_ids.len() as u64 + 1);
 
         let mut heap = BinaryHeap::new();
-        for (idx, reader) in readers.iter_mut().enumerate() {
-            if let Some(Ok(line)) = reader.next() {
-                let num: u64 = line.parse()?;
-                heap.push(Reverse((num, idx)));
+        for (run_idx, reader) in readers.iter_mut().enumerate() {
+            if let Some(record) = self.read_record(reader)? {
+                heap.push(HeapEntry {
+                    record,
+                    run_idx,
+                    reverse: self.reverse,
+                });
             }
         }
 
-        let mut output_file = std::fs::File::create(&self.output)?;
-
-        while let Some(item) = heap.pop() {
-            let (num, idx) = item.0;
-            writeln!(output_file, "{}", num)?;
+        let mut last_written: Option<Record> = None;
+        while let Some(HeapEntry { record, run_idx, .. }) = heap.pop() {
+            let suppress =
+                final_pass && self.unique && last_written.as_ref() == Some(&record);
+            if !suppress {
+                self.write_record(&mut output, &record, final_pass)?;
+            }
+            if final_pass && self.unique {
+                last_written = Some(record);
+            }
 
-            if let Some(Ok(line)) = readers[idx].next() {
-                let next_num = line.parse()?;
-                heap.push(Reverse((next_num, idx)));
+            if let Some(next_record) = self.read_record(&mut readers[run_idx])? {
+                heap.push(HeapEntry {
+                    record: next_record,
+                    run_idx,
+                    reverse: self.reverse,
+                });
             }
         }
 
-        for file_num in 0..files_num {
-            let temp_file_path = self.get_temp_file_path(file_num);
-            std::fs::remove_file(&temp_file_path)?;
+        for &file_num in run_ids {
+            std::fs::remove_file(self.get_temp_file_path(file_num))?;
         }
 
         Ok(())
     }
 }
 
-fn main() -> Result<()> {
-    let sorter = Sorter::parse();
+/// Parses a single `u64` out of a raw line slice, tolerating a trailing `\r`
+/// so CRLF-terminated inputs parse the same as LF-terminated ones.
+fn parse_u64_line(bytes: &[u8]) -> Result<u64> {
+    let text = std::str::from_utf8(bytes).context("input line is not valid UTF-8")?;
+    Ok(text.trim_end_matches('\r').parse()?)
+}
 
-    let file = std::fs::File::open(&sorter.input)?;
+/// Turns a raw input line into a `Record`, parsing it as a number in
+/// `--numeric` mode or keeping it as owned text otherwise.
+fn line_to_record(bytes: &[u8], numeric: bool) -> Result<Record> {
+    if numeric {
+        Ok(Record::Numeric(parse_u64_line(bytes)?))
+    } else {
+        let text = std::str::from_utf8(bytes).context("input line is not valid UTF-8")?;
+        Ok(Record::Lexical(text.trim_end_matches('\r').to_string()))
+    }
+}
 
-    let reader = BufReader::new(file);
-    let mut batch_sorted = Vec::with_capacity(sorter.batch);
-    let mut file_counter = 0;
+/// Reads one 8-byte little-endian `u64` from a run temp file, treating a
+/// clean end-of-file as the end of that run rather than an error.
+fn read_next_u64<R: Read>(reader: &mut R) -> Result<Option<u64>> {
+    let mut buf = [0u8; 8];
+    match reader.read_exact(&mut buf) {
+        Ok(()) => Ok(Some(u64::from_le_bytes(buf))),
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Reads `path` in fixed-size blocks and streams parsed `Record`s to the
+/// sort workers in batches of `batch_size` elements over `tx`. In `--numeric`
+/// mode records are parsed directly out of the raw bytes with no per-line
+/// allocation; lexical mode inherently needs one owned `String` per line.
+/// Batches are pulled from `recycle_rx` when available so the workers'
+/// already-spilled buffers get reused instead of reallocated, letting this
+/// thread keep reading while the workers sort and spill earlier batches.
+fn reader_thread(
+    path: String,
+    batch_size: usize,
+    numeric: bool,
+    tx: SyncSender<Vec<Record>>,
+    recycle_rx: Receiver<Vec<Record>>,
+    metrics: Arc<Metrics>,
+) -> Result<()> {
+    let mut file =
+        std::fs::File::open(&path).with_context(|| format!("failed to open {}", path))?;
+    let mut block = vec![0u8; READ_BLOCK_BYTES];
+    let mut carry: Vec<u8> = Vec::new();
+    let mut current = next_batch_buffer(&recycle_rx, batch_size);
+
+    loop {
+        let read = file.read(&mut block)?;
+        if read == 0 {
+            break;
+        }
+        metrics.bytes_read.fetch_add(read as u64, AtomicOrdering::Relaxed);
+
+        let data = &block[..read];
+        let mut offset = 0;
+        while let Some(rel_pos) = data[offset..].iter().position(|&b| b == b'\n') {
+            let line_end = offset + rel_pos;
+            let raw = &data[offset..line_end];
 
-    for line_result in reader.lines() {
-        let line = line_result?;
+            let record = if carry.is_empty() {
+                line_to_record(raw, numeric)?
+            } else {
+                carry.extend_from_slice(raw);
+                let record = line_to_record(&carry, numeric)?;
+                carry.clear();
+                record
+            };
 
-        let num: u64 = line.parse()?;
-        batch_sorted.push(num);
+            current.push(record);
+            metrics.values_read.fetch_add(1, AtomicOrdering::Relaxed);
+            if current.len() >= batch_size {
+                tx.send(current)?;
+                current = next_batch_buffer(&recycle_rx, batch_size);
+            }
 
-        if batch_sorted.len() >= sorter.batch {
-            sorter.sort_and_write_to_file(&mut batch_sorted, file_counter)?;
-            file_counter += 1;
-            batch_sorted.clear();
+            offset = line_end + 1;
         }
+
+        if offset < data.len() {
+            carry.extend_from_slice(&data[offset..]);
+        }
+    }
+
+    if !carry.is_empty() {
+        current.push(line_to_record(&carry, numeric)?);
+        metrics.values_read.fetch_add(1, AtomicOrdering::Relaxed);
+    }
+    if !current.is_empty() {
+        tx.send(current)?;
+    }
+
+    Ok(())
+}
+
+/// Grabs a recycled buffer from a previously flushed batch, falling back to
+/// a fresh allocation when none is available yet (e.g. on the first batch).
+fn next_batch_buffer(recycle_rx: &Receiver<Vec<Record>>, batch_size: usize) -> Vec<Record> {
+    recycle_rx
+        .try_recv()
+        .unwrap_or_else(|_| Vec::with_capacity(batch_size))
+}
+
+/// Pulls batches off the shared work queue and sorts/spills them until the
+/// queue is drained and disconnected, self-assigning each batch a unique
+/// run id from `next_file_num` so concurrent workers never collide on a
+/// temp file name.
+fn sort_worker(
+    sorter: Arc<Sorter>,
+    work_rx: Arc<Mutex<Receiver<Vec<Record>>>>,
+    next_file_num: Arc<AtomicU64>,
+    recycle_tx: mpsc::Sender<Vec<Record>>,
+    metrics: Arc<Metrics>,
+) -> Result<()> {
+    loop {
+        let batch = {
+            let rx = work_rx.lock().unwrap();
+            rx.recv()
+        };
+        let mut batch = match batch {
+            Ok(batch) => batch,
+            Err(_) => break,
+        };
+
+        let file_num = next_file_num.fetch_add(1, AtomicOrdering::SeqCst);
+        sorter.sort_and_write_to_file(&mut batch, file_num, &metrics)?;
+
+        batch.clear();
+        let _ = recycle_tx.send(batch);
     }
 
-    if !batch_sorted.is_empty() {
-        sorter.sort_and_write_to_file(&mut batch_sorted, file_counter)?;
-        file_counter += 1;
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let sorter = Arc::new(Sorter::parse());
+    let metrics = Arc::new(Metrics::default());
+
+    let (tx, rx) = mpsc::sync_channel::<Vec<Record>>(BATCH_CHANNEL_DEPTH);
+    let (recycle_tx, recycle_rx) = mpsc::channel::<Vec<Record>>();
+
+    let batch_size = sorter.batch;
+    let numeric = sorter.numeric;
+    let input_path = sorter.input.clone();
+    let read_spill_start = Instant::now();
+    let reader_metrics = Arc::clone(&metrics);
+    let reader_handle = thread::spawn(move || {
+        reader_thread(input_path, batch_size, numeric, tx, recycle_rx, reader_metrics)
+    });
+
+    let work_rx = Arc::new(Mutex::new(rx));
+    let next_file_num = Arc::new(AtomicU64::new(0));
+
+    let worker_handles: Vec<_> = (0..sorter.threads.max(1))
+        .map(|_| {
+            let sorter = Arc::clone(&sorter);
+            let work_rx = Arc::clone(&work_rx);
+            let next_file_num = Arc::clone(&next_file_num);
+            let recycle_tx = recycle_tx.clone();
+            let metrics = Arc::clone(&metrics);
+            thread::spawn(move || sort_worker(sorter, work_rx, next_file_num, recycle_tx, metrics))
+        })
+        .collect();
+    drop(recycle_tx);
+
+    reader_handle
+        .join()
+        .map_err(|_| anyhow::anyhow!("reader thread panicked"))??;
+
+    for handle in worker_handles {
+        handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("sort worker thread panicked"))??;
     }
+    *metrics.read_spill_elapsed.lock().unwrap() = read_spill_start.elapsed();
+
+    let merge_start = Instant::now();
+    let file_counter = next_file_num.load(AtomicOrdering::SeqCst);
+    sorter.merge_files(file_counter, &metrics)?;
+    *metrics.merge_elapsed.lock().unwrap() = merge_start.elapsed();
 
-    sorter.merge_files(file_counter)?;
+    if sorter.stats {
+        metrics.report();
+    }
 
     Ok(())
 }
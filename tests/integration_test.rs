@@ -68,7 +68,27 @@ fn read_numbers_from_file(filepath: &str) -> Vec<u64> {
         .collect()
 }
 
+fn read_lines_from_file(filepath: &str) -> Vec<String> {
+    let file = match File::open(filepath) {
+        Ok(f) => f,
+        Err(_) => return Vec::new(),
+    };
+    let reader = BufReader::new(file);
+
+    reader.lines().map_while(Result::ok).collect()
+}
+
 fn run_sorter(input: &str, output: &str, batch_size: Option<usize>) -> Result<bool, String> {
+    run_sorter_with_flags(input, output, batch_size, &["-n"])
+        .map(|process_output| process_output.status.success())
+}
+
+fn run_sorter_with_flags(
+    input: &str,
+    output: &str,
+    batch_size: Option<usize>,
+    extra_flags: &[&str],
+) -> Result<std::process::Output, String> {
     let mut cmd = Command::new("cargo");
     cmd.arg("run")
         .arg("--")
@@ -81,6 +101,10 @@ fn run_sorter(input: &str, output: &str, batch_size: Option<usize>) -> Result<bo
         cmd.arg("-b").arg(batch.to_string());
     }
 
+    for flag in extra_flags {
+        cmd.arg(flag);
+    }
+
     let output = cmd
         .output()
         .map_err(|e| format!("Failed to execute command: {}", e))?;
@@ -96,7 +120,7 @@ fn run_sorter(input: &str, output: &str, batch_size: Option<usize>) -> Result<bo
         ));
     }
 
-    Ok(output.status.success())
+    Ok(output)
 }
 
 #[test]
@@ -384,3 +408,192 @@ fn test_temp_file_cleanup() {
     cleanup_temp_files();
     cleanup_test_dir();
 }
+
+#[test]
+#[serial]
+fn test_multi_pass_merge() {
+    setup_test_dir();
+    cleanup_temp_files();
+
+    // Batch size 10 over 500 values spills 50 runs; a max-fanout of 3 forces
+    // several merge passes (50 -> 17 -> 6 -> 2 -> final) before the last
+    // pass writes to the output file, exercising merge_files' pass loop.
+    let numbers: Vec<u64> = (1..=500).rev().collect();
+    let input_file = create_test_file("multi_pass_input.txt", &numbers);
+    let output_file = format!("{}/multi_pass_output.txt", TEST_DIR);
+
+    match run_sorter_with_flags(&input_file, &output_file, Some(10), &["-n", "-f", "3"]) {
+        Ok(output) => assert!(output.status.success(), "Sorter should succeed"),
+        Err(e) => panic!("Failed to run sorter: {}", e),
+    }
+
+    let sorted_numbers = read_numbers_from_file(&output_file);
+    let expected: Vec<u64> = (1..=500).collect();
+    assert_eq!(sorted_numbers, expected);
+
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    let temp_files: Vec<_> = fs::read_dir(TEST_DIR)
+        .unwrap()
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let filename = path.file_name()?.to_str()?;
+            if filename.starts_with("sort_temp_file_") {
+                Some(filename.to_string())
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    assert!(
+        temp_files.is_empty(),
+        "Temp files not cleaned up after multi-pass merge: {:?}",
+        temp_files
+    );
+
+    cleanup_temp_files();
+    cleanup_test_dir();
+}
+
+#[test]
+#[serial]
+fn test_lexical_default_sort() {
+    setup_test_dir();
+    cleanup_temp_files();
+
+    let input_file = format!("{}/lexical_input.txt", TEST_DIR);
+    let mut file = File::create(&input_file).expect("Failed to create test file");
+    for line in ["banana", "10", "Apple", "apple", "9", "cherry"] {
+        writeln!(file, "{}", line).expect("Failed to write to test file");
+    }
+    drop(file);
+    let output_file = format!("{}/lexical_output.txt", TEST_DIR);
+
+    match run_sorter_with_flags(&input_file, &output_file, None, &[]) {
+        Ok(output) => assert!(output.status.success(), "Sorter should succeed"),
+        Err(e) => panic!("Failed to run sorter: {}", e),
+    }
+
+    let sorted_lines = read_lines_from_file(&output_file);
+    let mut expected = vec!["banana", "10", "Apple", "apple", "9", "cherry"];
+    expected.sort();
+    assert_eq!(sorted_lines, expected);
+
+    cleanup_temp_files();
+    cleanup_test_dir();
+}
+
+#[test]
+#[serial]
+fn test_reverse_flag() {
+    setup_test_dir();
+    cleanup_temp_files();
+
+    let input_file = format!("{}/reverse_flag_input.txt", TEST_DIR);
+    let mut file = File::create(&input_file).expect("Failed to create test file");
+    for line in ["apple", "banana", "cherry"] {
+        writeln!(file, "{}", line).expect("Failed to write to test file");
+    }
+    drop(file);
+    let output_file = format!("{}/reverse_flag_output.txt", TEST_DIR);
+
+    match run_sorter_with_flags(&input_file, &output_file, None, &["-r"]) {
+        Ok(output) => assert!(output.status.success(), "Sorter should succeed"),
+        Err(e) => panic!("Failed to run sorter: {}", e),
+    }
+
+    let sorted_lines = read_lines_from_file(&output_file);
+    assert_eq!(sorted_lines, vec!["cherry", "banana", "apple"]);
+
+    cleanup_temp_files();
+    cleanup_test_dir();
+}
+
+#[test]
+#[serial]
+fn test_unique_flag() {
+    setup_test_dir();
+    cleanup_temp_files();
+
+    let input_file = format!("{}/unique_input.txt", TEST_DIR);
+    let mut file = File::create(&input_file).expect("Failed to create test file");
+    for line in ["banana", "apple", "cherry", "apple", "banana"] {
+        writeln!(file, "{}", line).expect("Failed to write to test file");
+    }
+    drop(file);
+    let output_file = format!("{}/unique_output.txt", TEST_DIR);
+
+    match run_sorter_with_flags(&input_file, &output_file, None, &["-u"]) {
+        Ok(output) => assert!(output.status.success(), "Sorter should succeed"),
+        Err(e) => panic!("Failed to run sorter: {}", e),
+    }
+
+    let sorted_lines = read_lines_from_file(&output_file);
+    assert_eq!(sorted_lines, vec!["apple", "banana", "cherry"]);
+
+    cleanup_temp_files();
+    cleanup_test_dir();
+}
+
+#[test]
+#[serial]
+fn test_reverse_and_unique_flags() {
+    setup_test_dir();
+    cleanup_temp_files();
+
+    let input_file = format!("{}/reverse_unique_input.txt", TEST_DIR);
+    let mut file = File::create(&input_file).expect("Failed to create test file");
+    for line in ["banana", "apple", "cherry", "apple", "banana"] {
+        writeln!(file, "{}", line).expect("Failed to write to test file");
+    }
+    drop(file);
+    let output_file = format!("{}/reverse_unique_output.txt", TEST_DIR);
+
+    match run_sorter_with_flags(&input_file, &output_file, None, &["-r", "-u"]) {
+        Ok(output) => assert!(output.status.success(), "Sorter should succeed"),
+        Err(e) => panic!("Failed to run sorter: {}", e),
+    }
+
+    let sorted_lines = read_lines_from_file(&output_file);
+    assert_eq!(sorted_lines, vec!["cherry", "banana", "apple"]);
+
+    cleanup_temp_files();
+    cleanup_test_dir();
+}
+
+#[test]
+#[serial]
+fn test_stats_flag_reports_to_stderr() {
+    setup_test_dir();
+    cleanup_temp_files();
+
+    let numbers = vec![5, 3, 1, 4, 2];
+    let input_file = create_test_file("stats_input.txt", &numbers);
+    let output_file = format!("{}/stats_output.txt", TEST_DIR);
+
+    let with_stats = run_sorter_with_flags(&input_file, &output_file, None, &["-n", "-s"])
+        .expect("Sorter should succeed");
+    assert!(with_stats.status.success(), "Sorter should succeed");
+    let stderr = String::from_utf8_lossy(&with_stats.stderr);
+    assert!(
+        stderr.contains("sort statistics:"),
+        "Expected a stats report on stderr with --stats, got: {}",
+        stderr
+    );
+    assert_eq!(read_numbers_from_file(&output_file), vec![1, 2, 3, 4, 5]);
+
+    let without_stats = run_sorter_with_flags(&input_file, &output_file, None, &["-n"])
+        .expect("Sorter should succeed");
+    assert!(without_stats.status.success(), "Sorter should succeed");
+    let stderr = String::from_utf8_lossy(&without_stats.stderr);
+    assert!(
+        !stderr.contains("sort statistics:"),
+        "Expected no stats report on stderr without --stats, got: {}",
+        stderr
+    );
+    assert_eq!(read_numbers_from_file(&output_file), vec![1, 2, 3, 4, 5]);
+
+    cleanup_temp_files();
+    cleanup_test_dir();
+}